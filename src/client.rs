@@ -0,0 +1,574 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2015-2016 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::cell::RefCell;
+use std::fmt;
+
+use rand::{self, Rng};
+
+use sinks::MetricSink;
+use types::{validate_key, write_metric, Counter, Timer, Gauge, Meter, Set, Histogram, MetricBuilder,
+            AsMetricStr, MetricError, MetricResult};
+
+
+/// Roll the dice for a Statsd sample rate, returning `true` if a metric
+/// sampled at `rate` should actually be emitted this time around.
+///
+/// `rate` is assumed to have already been validated as being in the
+/// range `(0.0, 1.0]` (`Counter::new_sampled` and friends do this).
+fn should_sample(rate: f64) -> bool {
+    rate >= 1.0 || rand::thread_rng().gen::<f64>() < rate
+}
+
+
+thread_local! {
+    /// Buffer reused across calls to `StatsdClient::send_buffered` on a
+    /// given thread, so formatting a metric for the "fire and forget"
+    /// `*_buffered` methods doesn't allocate a new `String` every time.
+    #[allow(clippy::missing_const_for_thread_local)]
+    static METRIC_BUF: RefCell<String> = RefCell::new(String::new());
+}
+
+
+/// Client for emitting metrics to a Statsd server via a `MetricSink`.
+///
+/// See the `Counted`, `Timed`, `Gauged`, `Metered`, `Setted`, and
+/// `Histogrammed` traits for the methods this client exposes.
+pub struct StatsdClient<T: MetricSink> {
+    prefix: String,
+    sink: T,
+}
+
+
+impl<T: MetricSink> StatsdClient<T> {
+    /// Create a new client that will emit metrics prefixed with `prefix`
+    /// to the given sink.
+    pub fn from_sink(prefix: &str, sink: T) -> StatsdClient<T> {
+        StatsdClient {
+            prefix: prefix.to_string(),
+            sink,
+        }
+    }
+
+    fn send_metric<M: AsMetricStr>(&self, metric: M) -> MetricResult<M> {
+        match self.sink.emit(metric.as_metric_str()) {
+            Ok(_) => Ok(metric),
+            Err(e) => Err(MetricError::from(e)),
+        }
+    }
+
+    /// Attach `tags` to `metric` with a `MetricBuilder` and send the
+    /// resulting tagged metric string to the sink.
+    fn send_tagged<M: AsMetricStr>(&self, metric: M, tags: &[(&str, &str)]) -> MetricResult<String> {
+        let mut builder = MetricBuilder::new(metric);
+        for &(key, value) in tags {
+            builder = builder.with_tag(key, value);
+        }
+
+        let built = builder.build()?;
+        match self.sink.emit(&built) {
+            Ok(_) => Ok(built),
+            Err(e) => Err(MetricError::from(e)),
+        }
+    }
+
+    /// Format a metric with `write_metric` into a thread-local buffer and
+    /// send it to the sink, without allocating a new `String` per call (the
+    /// buffer is reused across calls on the same thread). Unlike
+    /// `send_metric`, this doesn't hand back a sentinel metric struct, just
+    /// whether the send succeeded, so it's a better fit for high-throughput
+    /// callers that don't need the returned value.
+    fn send_buffered<V: fmt::Display>(&self, key: &str, value: V, kind: &str) -> MetricResult<()> {
+        validate_key("prefix", &self.prefix)?;
+        validate_key("key", key)?;
+
+        METRIC_BUF.with(|buf| {
+            let mut buf = buf.borrow_mut();
+            buf.clear();
+            write_metric(&mut buf, &self.prefix, key, value, kind);
+
+            match self.sink.emit(&buf) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(MetricError::from(e)),
+            }
+        })
+    }
+}
+
+
+/// Trait for incrementing and decrementing counter metrics.
+pub trait Counted {
+    fn count(&self, key: &str, count: i64) -> MetricResult<Counter>;
+
+    fn incr(&self, key: &str) -> MetricResult<Counter> {
+        self.count(key, 1)
+    }
+
+    fn decr(&self, key: &str) -> MetricResult<Counter> {
+        self.count(key, -1)
+    }
+
+    /// Count, but only emitted to the server a fraction of the time
+    /// (given by `rate`, which must be in `(0.0, 1.0]`). A skipped
+    /// metric still returns `Ok`, carrying the counter that would have
+    /// been sent, so callers can keep chaining off of the result.
+    fn count_with_sampling(&self, key: &str, count: i64, rate: f64) -> MetricResult<Counter>;
+
+    /// Count, with DogStatsD-style tags (`key:value` pairs) attached to
+    /// the metric before it's sent.
+    fn count_with_tags(&self, key: &str, count: i64, tags: &[(&str, &str)]) -> MetricResult<String>;
+
+    /// Count, formatted into a reused buffer instead of allocating a new
+    /// `String`. Use this in place of `count` for high-throughput callers
+    /// that don't need the returned `Counter` back.
+    fn count_buffered(&self, key: &str, count: i64) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Counted for StatsdClient<T> {
+    fn count(&self, key: &str, count: i64) -> MetricResult<Counter> {
+        self.send_metric(Counter::new(&self.prefix, key, count)?)
+    }
+
+    fn count_with_sampling(&self, key: &str, count: i64, rate: f64) -> MetricResult<Counter> {
+        let metric = Counter::new_sampled(&self.prefix, key, count, rate)?;
+        if should_sample(rate) {
+            self.send_metric(metric)
+        } else {
+            Ok(metric)
+        }
+    }
+
+    fn count_with_tags(&self, key: &str, count: i64, tags: &[(&str, &str)]) -> MetricResult<String> {
+        self.send_tagged(Counter::new(&self.prefix, key, count)?, tags)
+    }
+
+    fn count_buffered(&self, key: &str, count: i64) -> MetricResult<()> {
+        self.send_buffered(key, count, "c")
+    }
+}
+
+
+/// Trait for recording timer metrics.
+pub trait Timed {
+    fn time(&self, key: &str, time: u64) -> MetricResult<Timer>;
+
+    /// Time, but only emitted to the server a fraction of the time
+    /// (given by `rate`, which must be in `(0.0, 1.0]`). A skipped
+    /// metric still returns `Ok`, carrying the timer that would have
+    /// been sent, so callers can keep chaining off of the result.
+    fn time_with_sampling(&self, key: &str, time: u64, rate: f64) -> MetricResult<Timer>;
+
+    /// Time, with DogStatsD-style tags (`key:value` pairs) attached to
+    /// the metric before it's sent.
+    fn time_with_tags(&self, key: &str, time: u64, tags: &[(&str, &str)]) -> MetricResult<String>;
+
+    /// Time, formatted into a reused buffer instead of allocating a new
+    /// `String`. Use this in place of `time` for high-throughput callers
+    /// that don't need the returned `Timer` back.
+    fn time_buffered(&self, key: &str, time: u64) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Timed for StatsdClient<T> {
+    fn time(&self, key: &str, time: u64) -> MetricResult<Timer> {
+        self.send_metric(Timer::new(&self.prefix, key, time)?)
+    }
+
+    fn time_with_sampling(&self, key: &str, time: u64, rate: f64) -> MetricResult<Timer> {
+        let metric = Timer::new_sampled(&self.prefix, key, time, rate)?;
+        if should_sample(rate) {
+            self.send_metric(metric)
+        } else {
+            Ok(metric)
+        }
+    }
+
+    fn time_with_tags(&self, key: &str, time: u64, tags: &[(&str, &str)]) -> MetricResult<String> {
+        self.send_tagged(Timer::new(&self.prefix, key, time)?, tags)
+    }
+
+    fn time_buffered(&self, key: &str, time: u64) -> MetricResult<()> {
+        self.send_buffered(key, time, "ms")
+    }
+}
+
+
+/// Trait for recording gauge metrics.
+pub trait Gauged {
+    fn gauge(&self, key: &str, value: u64) -> MetricResult<Gauge>;
+
+    /// Gauge, with DogStatsD-style tags (`key:value` pairs) attached to
+    /// the metric before it's sent.
+    fn gauge_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String>;
+
+    /// Gauge, formatted into a reused buffer instead of allocating a new
+    /// `String`. Use this in place of `gauge` for high-throughput callers
+    /// that don't need the returned `Gauge` back.
+    fn gauge_buffered(&self, key: &str, value: u64) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Gauged for StatsdClient<T> {
+    fn gauge(&self, key: &str, value: u64) -> MetricResult<Gauge> {
+        self.send_metric(Gauge::new(&self.prefix, key, value)?)
+    }
+
+    fn gauge_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String> {
+        self.send_tagged(Gauge::new(&self.prefix, key, value)?, tags)
+    }
+
+    fn gauge_buffered(&self, key: &str, value: u64) -> MetricResult<()> {
+        self.send_buffered(key, value, "g")
+    }
+}
+
+
+/// Trait for recording meter metrics.
+pub trait Metered {
+    fn meter(&self, key: &str, value: u64) -> MetricResult<Meter>;
+
+    /// Meter, with DogStatsD-style tags (`key:value` pairs) attached to
+    /// the metric before it's sent.
+    fn meter_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String>;
+
+    /// Meter, formatted into a reused buffer instead of allocating a new
+    /// `String`. Use this in place of `meter` for high-throughput callers
+    /// that don't need the returned `Meter` back.
+    fn meter_buffered(&self, key: &str, value: u64) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Metered for StatsdClient<T> {
+    fn meter(&self, key: &str, value: u64) -> MetricResult<Meter> {
+        self.send_metric(Meter::new(&self.prefix, key, value)?)
+    }
+
+    fn meter_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String> {
+        self.send_tagged(Meter::new(&self.prefix, key, value)?, tags)
+    }
+
+    fn meter_buffered(&self, key: &str, value: u64) -> MetricResult<()> {
+        self.send_buffered(key, value, "m")
+    }
+}
+
+
+/// Trait for recording set metrics, tracking the number of unique
+/// values seen for a key over a flush period.
+pub trait Setted {
+    /// Set, accepting anything that can be displayed as a string (a
+    /// numeric user ID, a UUID, an already-owned `String`, and so on),
+    /// not just a `&str`.
+    fn set(&self, key: &str, value: &dyn fmt::Display) -> MetricResult<Set>;
+
+    /// Set, with DogStatsD-style tags (`key:value` pairs) attached to
+    /// the metric before it's sent.
+    fn set_with_tags(&self,
+                      key: &str,
+                      value: &dyn fmt::Display,
+                      tags: &[(&str, &str)])
+                      -> MetricResult<String>;
+
+    /// Set, formatted into a reused buffer instead of allocating a new
+    /// `String`. Use this in place of `set` for high-throughput callers
+    /// that don't need the returned `Set` back.
+    fn set_buffered(&self, key: &str, value: &dyn fmt::Display) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Setted for StatsdClient<T> {
+    fn set(&self, key: &str, value: &dyn fmt::Display) -> MetricResult<Set> {
+        self.send_metric(Set::new(&self.prefix, key, value.to_string())?)
+    }
+
+    fn set_with_tags(&self,
+                      key: &str,
+                      value: &dyn fmt::Display,
+                      tags: &[(&str, &str)])
+                      -> MetricResult<String> {
+        self.send_tagged(Set::new(&self.prefix, key, value.to_string())?, tags)
+    }
+
+    fn set_buffered(&self, key: &str, value: &dyn fmt::Display) -> MetricResult<()> {
+        self.send_buffered(key, value, "s")
+    }
+}
+
+
+/// Trait for recording histogram metrics.
+pub trait Histogrammed {
+    fn histogram(&self, key: &str, value: u64) -> MetricResult<Histogram>;
+
+    /// Histogram, but only emitted to the server a fraction of the time
+    /// (given by `rate`, which must be in `(0.0, 1.0]`). A skipped
+    /// metric still returns `Ok`, carrying the histogram value that
+    /// would have been sent, so callers can keep chaining off of the
+    /// result.
+    fn histogram_with_sampling(&self, key: &str, value: u64, rate: f64) -> MetricResult<Histogram>;
+
+    /// Histogram, with DogStatsD-style tags (`key:value` pairs) attached
+    /// to the metric before it's sent.
+    fn histogram_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String>;
+
+    /// Histogram, formatted into a reused buffer instead of allocating a
+    /// new `String`. Use this in place of `histogram` for high-throughput
+    /// callers that don't need the returned `Histogram` back.
+    fn histogram_buffered(&self, key: &str, value: u64) -> MetricResult<()>;
+}
+
+
+impl<T: MetricSink> Histogrammed for StatsdClient<T> {
+    fn histogram(&self, key: &str, value: u64) -> MetricResult<Histogram> {
+        self.send_metric(Histogram::new(&self.prefix, key, value)?)
+    }
+
+    fn histogram_with_sampling(&self, key: &str, value: u64, rate: f64) -> MetricResult<Histogram> {
+        let metric = Histogram::new_sampled(&self.prefix, key, value, rate)?;
+        if should_sample(rate) {
+            self.send_metric(metric)
+        } else {
+            Ok(metric)
+        }
+    }
+
+    fn histogram_with_tags(&self, key: &str, value: u64, tags: &[(&str, &str)]) -> MetricResult<String> {
+        self.send_tagged(Histogram::new(&self.prefix, key, value)?, tags)
+    }
+
+    fn histogram_buffered(&self, key: &str, value: u64) -> MetricResult<()> {
+        self.send_buffered(key, value, "h")
+    }
+}
+
+
+/// Trait that combines all of the metric traits this crate supports into
+/// one. Useful for referring to any Cadence client by a single trait
+/// object type (for example, to swap in a dummy client in tests).
+pub trait MetricClient: Counted + Timed + Gauged + Metered + Setted + Histogrammed {}
+
+
+impl<T> MetricClient for T where T: Counted + Timed + Gauged + Metered + Setted + Histogrammed {}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::io;
+
+    use types::AsMetricStr;
+    use super::{StatsdClient, MetricSink, Counted, Timed, Gauged, Metered, Setted, Histogrammed};
+
+    struct RecordingSink {
+        emitted: RefCell<Vec<String>>,
+    }
+
+    impl RecordingSink {
+        fn new() -> RecordingSink {
+            RecordingSink { emitted: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl MetricSink for RecordingSink {
+        fn emit(&self, metric: &str) -> io::Result<usize> {
+            self.emitted.borrow_mut().push(metric.to_string());
+            Ok(metric.len())
+        }
+    }
+
+    #[test]
+    fn test_statsd_client_counted() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.incr("some.counter").unwrap();
+        assert_eq!(vec!["my.app.some.counter:1|c"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_timed() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.time("some.operation", 42).unwrap();
+        assert_eq!(vec!["my.app.some.operation:42|ms"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_gauged() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.gauge("some.thing", 7).unwrap();
+        assert_eq!(vec!["my.app.some.thing:7|g"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_metered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.meter("some.value", 5).unwrap();
+        assert_eq!(vec!["my.app.some.value:5|m"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_setted() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.set("unique.users", &"12345").unwrap();
+        assert_eq!(vec!["my.app.unique.users:12345|s"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_histogrammed() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.histogram("payload.size", 2048).unwrap();
+        assert_eq!(vec!["my.app.payload.size:2048|h"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_count_with_sampling_rate_of_one_always_sends() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let counter = client.count_with_sampling("some.counter", 4, 1.0).unwrap();
+        assert_eq!("my.app.some.counter:4|c|@1", counter.as_metric_str());
+        assert_eq!(vec!["my.app.some.counter:4|c|@1"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_count_with_sampling_rejects_invalid_rate() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        assert!(client.count_with_sampling("some.counter", 4, 0.0).is_err());
+        assert!(client.sink.emitted.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_statsd_client_time_with_sampling_rate_of_one_always_sends() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.time_with_sampling("some.operation", 42, 1.0).unwrap();
+        assert_eq!(vec!["my.app.some.operation:42|ms|@1"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_histogram_with_sampling_rate_of_one_always_sends() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.histogram_with_sampling("payload.size", 2048, 1.0).unwrap();
+        assert_eq!(vec!["my.app.payload.size:2048|h|@1"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_count_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.count_with_tags("some.counter", 4, &[("region", "us-east")]).unwrap();
+        assert_eq!("my.app.some.counter:4|c|#region:us-east", built);
+        assert_eq!(vec!["my.app.some.counter:4|c|#region:us-east"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_time_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.time_with_tags("some.operation", 42, &[("host", "web01")]).unwrap();
+        assert_eq!("my.app.some.operation:42|ms|#host:web01", built);
+    }
+
+    #[test]
+    fn test_statsd_client_gauge_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.gauge_with_tags("some.thing", 7, &[("host", "web01")]).unwrap();
+        assert_eq!("my.app.some.thing:7|g|#host:web01", built);
+    }
+
+    #[test]
+    fn test_statsd_client_meter_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.meter_with_tags("some.value", 5, &[("host", "web01")]).unwrap();
+        assert_eq!("my.app.some.value:5|m|#host:web01", built);
+    }
+
+    #[test]
+    fn test_statsd_client_setted_with_numeric_value() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.set("unique.users", &42u64).unwrap();
+        assert_eq!(vec!["my.app.unique.users:42|s"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_set_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.set_with_tags("unique.users", &"12345", &[("region", "us-east")]).unwrap();
+        assert_eq!("my.app.unique.users:12345|s|#region:us-east", built);
+    }
+
+    #[test]
+    fn test_statsd_client_histogram_with_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client.histogram_with_tags("payload.size", 2048, &[("region", "us-east")]).unwrap();
+        assert_eq!("my.app.payload.size:2048|h|#region:us-east", built);
+    }
+
+    #[test]
+    fn test_statsd_client_count_with_tags_and_multiple_tags() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        let built = client
+            .count_with_tags("some.counter", 4, &[("region", "us-east"), ("host", "web01")])
+            .unwrap();
+        assert_eq!("my.app.some.counter:4|c|#region:us-east,host:web01", built);
+    }
+
+    #[test]
+    fn test_statsd_client_count_buffered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.count_buffered("some.counter", 4).unwrap();
+        assert_eq!(vec!["my.app.some.counter:4|c"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_count_buffered_reuses_buffer_across_calls() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.count_buffered("some.counter", 4).unwrap();
+        client.time_buffered("some.operation", 42).unwrap();
+        assert_eq!(vec!["my.app.some.counter:4|c", "my.app.some.operation:42|ms"],
+                   *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_count_buffered_rejects_invalid_key() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        assert!(client.count_buffered("some:counter", 4).is_err());
+        assert!(client.sink.emitted.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_statsd_client_gauge_buffered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.gauge_buffered("some.thing", 7).unwrap();
+        assert_eq!(vec!["my.app.some.thing:7|g"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_meter_buffered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.meter_buffered("some.value", 5).unwrap();
+        assert_eq!(vec!["my.app.some.value:5|m"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_set_buffered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.set_buffered("unique.users", &"12345").unwrap();
+        assert_eq!(vec!["my.app.unique.users:12345|s"], *client.sink.emitted.borrow());
+    }
+
+    #[test]
+    fn test_statsd_client_histogram_buffered() {
+        let client = StatsdClient::from_sink("my.app", RecordingSink::new());
+        client.histogram_buffered("payload.size", 2048).unwrap();
+        assert_eq!(vec!["my.app.payload.size:2048|h"], *client.sink.emitted.borrow());
+    }
+}
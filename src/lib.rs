@@ -30,7 +30,8 @@
 //!
 //! ## Features
 //!
-//! * Support for emitting counters, timers, gauges, and meters to Statsd over UDP.
+//! * Support for emitting counters, timers, gauges, meters, sets, and
+//!   histograms to Statsd over UDP.
 //! * Support for alternate backends via the `MetricSink` trait.
 //! * A simple yet flexible API for sending metrics.
 //!
@@ -291,14 +292,15 @@
 
 #[macro_use]
 extern crate log;
+extern crate rand;
 extern crate threadpool;
 
 
 pub const DEFAULT_PORT: u16 = 8125;
 
 
-pub use self::client::{Counted, Timed, Gauged, Metered, MetricClient,
-                       StatsdClient};
+pub use self::client::{Counted, Timed, Gauged, Metered, Setted, Histogrammed,
+                       MetricClient, StatsdClient};
 
 
 pub use self::sinks::{MetricSink, ConsoleMetricSink, LoggingMetricSink,
@@ -307,7 +309,8 @@ pub use self::sinks::{MetricSink, ConsoleMetricSink, LoggingMetricSink,
 
 
 pub use self::types::{MetricResult, MetricError, ErrorKind, Counter, Timer,
-                      Gauge, Meter};
+                      Gauge, Meter, Set, Histogram, MetricBuilder, validate_key,
+                      validate_tag, validate_rate, write_metric, write_metric_sampled};
 
 
 pub mod prelude;
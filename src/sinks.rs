@@ -0,0 +1,27 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2015-2016 TSH Labs
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::io;
+
+
+/// Trait for the underlying transport a `StatsdClient` writes metrics to.
+///
+/// Implementations just need to know how to write a complete Statsd metric
+/// line somewhere, be that a UDP socket, stdout, or a buffer in a test.
+pub trait MetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize>;
+}
@@ -17,6 +17,7 @@
 
 use std::error;
 use std::fmt;
+use std::fmt::Write as FmtWrite;
 use std::io;
 
 
@@ -28,6 +29,30 @@ pub trait AsMetricStr {
     fn as_metric_str(&self) -> &str;
 }
 
+
+/// Write a Statsd metric line into `buf` without allocating a new `String`.
+///
+/// This is the low-level building block `Counter::new` and the other metric
+/// constructors use internally. Clients that emit a high volume of metrics
+/// can call this directly with a reused buffer (for example, one owned by a
+/// buffered sink) instead of allocating a fresh `String` per metric.
+pub fn write_metric<V: fmt::Display>(buf: &mut String, prefix: &str, key: &str, value: V, kind: &str) {
+    let _ = write!(buf, "{}.{}:{}|{}", prefix, key, value, kind);
+}
+
+
+/// Like `write_metric`, but for a metric carrying a Statsd sample rate
+/// (the `|@<rate>` suffix).
+pub fn write_metric_sampled<V: fmt::Display>(buf: &mut String,
+                                              prefix: &str,
+                                              key: &str,
+                                              value: V,
+                                              kind: &str,
+                                              rate: f64) {
+    let _ = write!(buf, "{}.{}:{}|{}|@{}", prefix, key, value, kind, rate);
+}
+
+
 /// Counters are simple values incremented or decremented by a client.
 ///
 /// See the `Counted` trait for more information.
@@ -38,8 +63,28 @@ pub struct Counter {
 
 
 impl Counter {
-    pub fn new(prefix: &str, key: &str, count: i64) -> Counter {
-        Counter { repr: format!("{}.{}:{}|c", prefix, key, count) }
+    /// Create a new counter. Returns an error if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol (`:`, `|`, `@`).
+    pub fn new(prefix: &str, key: &str, count: i64) -> MetricResult<Counter> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, count, "c");
+        Ok(Counter { repr })
+    }
+
+    /// Create a new counter that will only be sent to the server some
+    /// fraction of the time, indicated by `rate` (a value between `0.0`
+    /// and `1.0`, exclusive and inclusive respectively). Returns an
+    /// error if `rate` is outside of that range, or if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol.
+    pub fn new_sampled(prefix: &str, key: &str, count: i64, rate: f64) -> MetricResult<Counter> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        validate_rate(rate)?;
+        let mut repr = String::new();
+        write_metric_sampled(&mut repr, prefix, key, count, "c", rate);
+        Ok(Counter { repr })
     }
 }
 
@@ -61,8 +106,28 @@ pub struct Timer {
 
 
 impl Timer {
-    pub fn new(prefix: &str, key: &str, time: u64) -> Timer {
-        Timer { repr: format!("{}.{}:{}|ms", prefix, key, time) }
+    /// Create a new timer. Returns an error if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol (`:`, `|`, `@`).
+    pub fn new(prefix: &str, key: &str, time: u64) -> MetricResult<Timer> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, time, "ms");
+        Ok(Timer { repr })
+    }
+
+    /// Create a new timer that will only be sent to the server some
+    /// fraction of the time, indicated by `rate` (a value between `0.0`
+    /// and `1.0`, exclusive and inclusive respectively). Returns an
+    /// error if `rate` is outside of that range, or if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol.
+    pub fn new_sampled(prefix: &str, key: &str, time: u64, rate: f64) -> MetricResult<Timer> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        validate_rate(rate)?;
+        let mut repr = String::new();
+        write_metric_sampled(&mut repr, prefix, key, time, "ms", rate);
+        Ok(Timer { repr })
     }
 }
 
@@ -84,8 +149,14 @@ pub struct Gauge {
 
 
 impl Gauge {
-    pub fn new(prefix: &str, key: &str, value: u64) -> Gauge {
-        Gauge { repr: format!("{}.{}:{}|g", prefix, key, value) }
+    /// Create a new gauge. Returns an error if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol (`:`, `|`, `@`).
+    pub fn new(prefix: &str, key: &str, value: u64) -> MetricResult<Gauge> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, value, "g");
+        Ok(Gauge { repr })
     }
 }
 
@@ -107,8 +178,14 @@ pub struct Meter {
 
 
 impl Meter {
-    pub fn new(prefix: &str, key: &str, value: u64) -> Meter {
-        Meter { repr: format!("{}.{}:{}|m", prefix, key, value) }
+    /// Create a new meter. Returns an error if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol (`:`, `|`, `@`).
+    pub fn new(prefix: &str, key: &str, value: u64) -> MetricResult<Meter> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, value, "m");
+        Ok(Meter { repr })
     }
 }
 
@@ -120,10 +197,134 @@ impl AsMetricStr for Meter {
 }
 
 
+/// Sets count the number of unique elements in a group.
+///
+/// See the `Setted` trait for more information.
+#[derive(PartialEq, Eq, Debug, Hash)]
+pub struct Set {
+    repr: String,
+}
+
+
+impl Set {
+    /// Create a new set value. Returns an error if `prefix` or `key`
+    /// contain a character reserved by the Statsd protocol (`:`, `|`, `@`).
+    pub fn new<T: ToString>(prefix: &str, key: &str, value: T) -> MetricResult<Set> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, value.to_string(), "s");
+        Ok(Set { repr })
+    }
+}
+
+
+impl AsMetricStr for Set {
+    fn as_metric_str(&self) -> &str {
+        &self.repr
+    }
+}
+
+
+/// Histograms measure the statistical distribution of a set of values.
+///
+/// See the `Histogrammed` trait for more information.
+#[derive(PartialEq, Eq, Debug, Hash)]
+pub struct Histogram {
+    repr: String,
+}
+
+
+impl Histogram {
+    /// Create a new histogram value. Returns an error if `prefix` or
+    /// `key` contain a character reserved by the Statsd protocol
+    /// (`:`, `|`, `@`).
+    pub fn new(prefix: &str, key: &str, value: u64) -> MetricResult<Histogram> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        let mut repr = String::new();
+        write_metric(&mut repr, prefix, key, value, "h");
+        Ok(Histogram { repr })
+    }
+
+    /// Create a new histogram value that will only be sent to the server
+    /// some fraction of the time, indicated by `rate` (a value between
+    /// `0.0` and `1.0`, exclusive and inclusive respectively). Returns
+    /// an error if `rate` is outside of that range, or if `prefix` or
+    /// `key` contain a character reserved by the Statsd protocol.
+    pub fn new_sampled(prefix: &str, key: &str, value: u64, rate: f64) -> MetricResult<Histogram> {
+        validate_key("prefix", prefix)?;
+        validate_key("key", key)?;
+        validate_rate(rate)?;
+        let mut repr = String::new();
+        write_metric_sampled(&mut repr, prefix, key, value, "h", rate);
+        Ok(Histogram { repr })
+    }
+}
+
+
+impl AsMetricStr for Histogram {
+    fn as_metric_str(&self) -> &str {
+        &self.repr
+    }
+}
+
+
+/// Builder for attaching DogStatsD-style tags to a metric before it is sent.
+///
+/// Tags are rendered as a `|#key:value,key2:value2` suffix, appended after
+/// any sample rate the metric might already carry. Use `with_tag` to add
+/// tags one at a time, then `build` to produce the final metric string.
+pub struct MetricBuilder<T: AsMetricStr> {
+    metric: T,
+    tags: Vec<(String, String)>,
+}
+
+
+impl<T: AsMetricStr> MetricBuilder<T> {
+    pub fn new(metric: T) -> MetricBuilder<T> {
+        MetricBuilder {
+            metric,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Add a tag to this metric, returning the builder so calls can be chained.
+    pub fn with_tag<K, V>(mut self, key: K, value: V) -> MetricBuilder<T>
+        where K: Into<String>,
+              V: Into<String>
+    {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Render this metric, with any tags that have been added, as a
+    /// Statsd metric string. Returns an error if any tag key or value
+    /// contains a character reserved by the Statsd protocol (`:`, `|`,
+    /// `@`) or the `,` used to separate tags from one another.
+    pub fn build(self) -> MetricResult<String> {
+        if self.tags.is_empty() {
+            return Ok(self.metric.as_metric_str().to_string());
+        }
+
+        let mut tags = Vec::with_capacity(self.tags.len());
+        for (key, value) in &self.tags {
+            validate_tag("tag key", key)?;
+            validate_tag("tag value", value)?;
+            tags.push(format!("{}:{}", key, value));
+        }
+
+        Ok(format!("{}|#{}", self.metric.as_metric_str(), tags.join(",")))
+    }
+}
+
+
 /// Potential categories an error from this library falls into.
 #[derive(PartialEq, Eq, Debug, Hash, Clone, Copy)]
 pub enum ErrorKind {
     InvalidInput,
+    InvalidMetricKey,
+    InvalidSampleRate,
     IoError,
 }
 
@@ -139,6 +340,7 @@ pub struct MetricError {
 #[derive(Debug)]
 enum ErrorRepr {
     WithDescription(ErrorKind, &'static str),
+    WithDescriptionOwned(ErrorKind, String),
     IoError(io::Error),
 }
 
@@ -149,6 +351,23 @@ impl MetricError {
         match self.repr {
             ErrorRepr::IoError(_) => ErrorKind::IoError,
             ErrorRepr::WithDescription(kind, _) => kind,
+            ErrorRepr::WithDescriptionOwned(kind, _) => kind,
+        }
+    }
+
+    fn invalid_key(field: &str, value: &str, reason: char) -> MetricError {
+        MetricError {
+            repr: ErrorRepr::WithDescriptionOwned(
+                ErrorKind::InvalidMetricKey,
+                format!("{} '{}' contains reserved character '{}'", field, value, reason)),
+        }
+    }
+
+    fn invalid_sample_rate(rate: f64) -> MetricError {
+        MetricError {
+            repr: ErrorRepr::WithDescriptionOwned(
+                ErrorKind::InvalidSampleRate,
+                format!("sample rate '{}' is not in the range (0.0, 1.0]", rate)),
         }
     }
 }
@@ -159,6 +378,7 @@ impl fmt::Display for MetricError {
         match self.repr {
             ErrorRepr::IoError(ref err) => err.fmt(f),
             ErrorRepr::WithDescription(_, desc) => desc.fmt(f),
+            ErrorRepr::WithDescriptionOwned(_, ref desc) => desc.fmt(f),
         }
     }
 }
@@ -169,6 +389,7 @@ impl error::Error for MetricError {
         match self.repr {
             ErrorRepr::IoError(ref err) => err.description(),
             ErrorRepr::WithDescription(_, desc) => desc,
+            ErrorRepr::WithDescriptionOwned(_, ref desc) => desc,
         }
     }
 
@@ -198,32 +419,239 @@ impl From<(ErrorKind, &'static str)> for MetricError {
 pub type MetricResult<T> = Result<T, MetricError>;
 
 
+/// Characters reserved by the Statsd protocol that can't appear in a
+/// metric prefix or key without corrupting the wire format.
+const RESERVED_CHARS: &[char] = &[':', '|', '@'];
+
+
+/// Validate that a metric prefix or key doesn't contain any character
+/// reserved by the Statsd protocol (`:`, `|`, `@`).
+///
+/// `field` is the name of the thing being validated (e.g. `"key"` or
+/// `"prefix"`) and is only used to produce a more helpful error message.
+pub fn validate_key(field: &str, value: &str) -> MetricResult<()> {
+    match value.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        Some(c) => Err(MetricError::invalid_key(field, value, c)),
+        None => Ok(()),
+    }
+}
+
+
+/// Characters reserved for separating one DogStatsD tag from another,
+/// on top of the characters already reserved by the Statsd protocol.
+const TAG_RESERVED_CHARS: &[char] = &[':', '|', '@', ','];
+
+
+/// Validate that a DogStatsD tag key or value doesn't contain any
+/// character reserved by the Statsd protocol (`:`, `|`, `@`) or the `,`
+/// used to separate one tag from the next.
+///
+/// `field` is the name of the thing being validated (e.g. `"tag key"` or
+/// `"tag value"`) and is only used to produce a more helpful error message.
+pub fn validate_tag(field: &str, value: &str) -> MetricResult<()> {
+    match value.chars().find(|c| TAG_RESERVED_CHARS.contains(c)) {
+        Some(c) => Err(MetricError::invalid_key(field, value, c)),
+        None => Ok(()),
+    }
+}
+
+
+/// Validate that a Statsd sample rate falls in the valid range of
+/// `(0.0, 1.0]` (a rate of `0.0` would mean a metric is never sent, so
+/// it's rejected rather than silently accepted).
+pub fn validate_rate(rate: f64) -> MetricResult<()> {
+    if rate > 0.0 && rate <= 1.0 {
+        Ok(())
+    } else {
+        Err(MetricError::invalid_sample_rate(rate))
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
 
-    use super::{Counter, Timer, Gauge, Meter, AsMetricStr};
+    use super::{Counter, Timer, Gauge, Meter, Set, Histogram, MetricBuilder, AsMetricStr,
+                ErrorKind, validate_key, validate_tag, validate_rate, write_metric,
+                write_metric_sampled};
 
     #[test]
     fn test_counter_to_metric_string() {
-        let counter = Counter::new("my.app", "test.counter", 4);
+        let counter = Counter::new("my.app", "test.counter", 4).unwrap();
         assert_eq!("my.app.test.counter:4|c", counter.as_metric_str());
     }
 
+    #[test]
+    fn test_counter_rejects_invalid_key() {
+        assert_eq!(ErrorKind::InvalidMetricKey,
+                   Counter::new("my.app", "test:counter", 4).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_counter_to_metric_string_with_sampling() {
+        let counter = Counter::new_sampled("my.app", "test.counter", 4, 0.1).unwrap();
+        assert_eq!("my.app.test.counter:4|c|@0.1", counter.as_metric_str());
+    }
+
+    #[test]
+    fn test_counter_with_sampling_rejects_out_of_range_rate() {
+        assert_eq!(ErrorKind::InvalidSampleRate,
+                   Counter::new_sampled("my.app", "test.counter", 4, 0.0).unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidSampleRate,
+                   Counter::new_sampled("my.app", "test.counter", 4, 1.1).unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidSampleRate,
+                   Counter::new_sampled("my.app", "test.counter", 4, -0.5).unwrap_err().kind());
+    }
+
     #[test]
     fn test_timer_to_metric_string() {
-        let timer = Timer::new("my.app", "test.timer", 34);
+        let timer = Timer::new("my.app", "test.timer", 34).unwrap();
         assert_eq!("my.app.test.timer:34|ms", timer.as_metric_str());
     }
 
+    #[test]
+    fn test_timer_to_metric_string_with_sampling() {
+        let timer = Timer::new_sampled("my.app", "test.timer", 34, 0.5).unwrap();
+        assert_eq!("my.app.test.timer:34|ms|@0.5", timer.as_metric_str());
+    }
+
     #[test]
     fn test_gauge_to_metric_string() {
-        let gauge = Gauge::new("my.app", "test.gauge", 2);
+        let gauge = Gauge::new("my.app", "test.gauge", 2).unwrap();
         assert_eq!("my.app.test.gauge:2|g", gauge.as_metric_str());
     }
 
     #[test]
     fn test_meter_to_metric_string() {
-        let meter = Meter::new("my.app", "test.meter", 5);
+        let meter = Meter::new("my.app", "test.meter", 5).unwrap();
         assert_eq!("my.app.test.meter:5|m", meter.as_metric_str());
     }
+
+    #[test]
+    fn test_set_to_metric_string() {
+        let set = Set::new("my.app", "test.set", "unique.value").unwrap();
+        assert_eq!("my.app.test.set:unique.value|s", set.as_metric_str());
+    }
+
+    #[test]
+    fn test_set_to_metric_string_with_numeric_value() {
+        let set = Set::new("my.app", "test.set", 42).unwrap();
+        assert_eq!("my.app.test.set:42|s", set.as_metric_str());
+    }
+
+    #[test]
+    fn test_histogram_to_metric_string() {
+        let histogram = Histogram::new("my.app", "test.histogram", 4096).unwrap();
+        assert_eq!("my.app.test.histogram:4096|h", histogram.as_metric_str());
+    }
+
+    #[test]
+    fn test_histogram_to_metric_string_with_sampling() {
+        let histogram = Histogram::new_sampled("my.app", "test.histogram", 4096, 0.25).unwrap();
+        assert_eq!("my.app.test.histogram:4096|h|@0.25", histogram.as_metric_str());
+    }
+
+    #[test]
+    fn test_metric_builder_with_no_tags() {
+        let counter = Counter::new("my.app", "test.counter", 4).unwrap();
+        let built = MetricBuilder::new(counter).build().unwrap();
+        assert_eq!("my.app.test.counter:4|c", built);
+    }
+
+    #[test]
+    fn test_metric_builder_with_one_tag() {
+        let counter = Counter::new("my.app", "test.counter", 4).unwrap();
+        let built = MetricBuilder::new(counter).with_tag("region", "us-east").build().unwrap();
+        assert_eq!("my.app.test.counter:4|c|#region:us-east", built);
+    }
+
+    #[test]
+    fn test_metric_builder_with_multiple_tags() {
+        let timer = Timer::new("my.app", "test.timer", 34).unwrap();
+        let built = MetricBuilder::new(timer)
+            .with_tag("region", "us-east")
+            .with_tag("host", "web01")
+            .build()
+            .unwrap();
+        assert_eq!("my.app.test.timer:34|ms|#region:us-east,host:web01", built);
+    }
+
+    #[test]
+    fn test_metric_builder_with_sampled_metric() {
+        let counter = Counter::new_sampled("my.app", "test.counter", 4, 0.1).unwrap();
+        let built = MetricBuilder::new(counter).with_tag("region", "us-east").build().unwrap();
+        assert_eq!("my.app.test.counter:4|c|@0.1|#region:us-east", built);
+    }
+
+    #[test]
+    fn test_metric_builder_rejects_tag_value_with_comma() {
+        let counter = Counter::new("my.app", "test.counter", 4).unwrap();
+        let err = MetricBuilder::new(counter).with_tag("path", "a,b").build().unwrap_err();
+        assert_eq!(ErrorKind::InvalidMetricKey, err.kind());
+    }
+
+    #[test]
+    fn test_validate_tag_with_valid_tag() {
+        assert!(validate_tag("tag key", "region").is_ok());
+    }
+
+    #[test]
+    fn test_validate_tag_with_comma() {
+        let err = validate_tag("tag value", "a,b").unwrap_err();
+        assert_eq!(ErrorKind::InvalidMetricKey, err.kind());
+    }
+
+    #[test]
+    fn test_validate_key_with_valid_key() {
+        assert!(validate_key("key", "some.valid.key").is_ok());
+    }
+
+    #[test]
+    fn test_validate_key_with_colon() {
+        let err = validate_key("key", "some:invalid:key").unwrap_err();
+        assert_eq!(ErrorKind::InvalidMetricKey, err.kind());
+    }
+
+    #[test]
+    fn test_validate_key_with_pipe() {
+        let err = validate_key("key", "some|invalid|key").unwrap_err();
+        assert_eq!(ErrorKind::InvalidMetricKey, err.kind());
+    }
+
+    #[test]
+    fn test_validate_key_with_at_sign() {
+        let err = validate_key("prefix", "some@invalid@prefix").unwrap_err();
+        assert_eq!(ErrorKind::InvalidMetricKey, err.kind());
+    }
+
+    #[test]
+    fn test_write_metric_reuses_buffer() {
+        let mut buf = String::new();
+        write_metric(&mut buf, "my.app", "test.counter", 4, "c");
+        assert_eq!("my.app.test.counter:4|c", buf);
+
+        buf.clear();
+        write_metric(&mut buf, "my.app", "test.gauge", 7, "g");
+        assert_eq!("my.app.test.gauge:7|g", buf);
+    }
+
+    #[test]
+    fn test_validate_rate_with_valid_rates() {
+        assert!(validate_rate(1.0).is_ok());
+        assert!(validate_rate(0.01).is_ok());
+    }
+
+    #[test]
+    fn test_validate_rate_with_invalid_rates() {
+        assert_eq!(ErrorKind::InvalidSampleRate, validate_rate(0.0).unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidSampleRate, validate_rate(1.1).unwrap_err().kind());
+        assert_eq!(ErrorKind::InvalidSampleRate, validate_rate(-1.0).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_write_metric_sampled() {
+        let mut buf = String::new();
+        write_metric_sampled(&mut buf, "my.app", "test.counter", 4, "c", 0.1);
+        assert_eq!("my.app.test.counter:4|c|@0.1", buf);
+    }
 }